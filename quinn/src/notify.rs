@@ -1,48 +1,226 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
 use std::sync::{Arc, Mutex};
-use std::task::{Context, Waker};
+use std::task::{Context, Poll, Waker};
 
 use slab::Slab;
 
-/// Broadcasts an event to any number of waiters
+/// Number of independently-versioned interest bits tracked by a [`NotifyOwned`]
+const INTEREST_BITS: usize = 32;
+
+/// Per-bit version counters
+type Versions = [u64; INTEREST_BITS];
+
+/// Broadcasts an event to any number of waiters, optionally filtered by an interest bitmask
+///
+/// A single `NotifyOwned` can multiplex several independent readiness directions (e.g.
+/// "readable", "writable", "closed") by having each waiter register interest in a subset of the
+/// 32 bits and calling `notify_mask` with only the bits that actually became ready, rather than
+/// allocating one `NotifyOwned` per condition.
 #[derive(Clone)]
 pub struct NotifyOwned {
     shared: Arc<Mutex<Shared>>,
 }
 
 struct Shared {
-    version: u64,
-    wakers: Slab<Waker>,
+    /// Per-bit version counters, incremented by `notify_mask` for every bit it targets
+    versions: Versions,
+    wakers: Slab<Entry>,
+    /// Slab keys in registration order, so `notify_one` can find the oldest registered waiter in
+    /// amortized O(1) instead of scanning every entry. May hold stale keys for waiters already
+    /// removed by `notify_mask`/`Waiter::drop`; `notify_one` skips those as it pops from the front.
+    order: VecDeque<usize>,
+    /// Set by `notify_one` when no waiter is registered, so the next `Waiter` to poll with
+    /// interest `u32::MAX` observes readiness immediately instead of losing the wakeup.
+    /// Saturates at 1.
+    permits: usize,
+}
+
+struct Entry {
+    waker: Waker,
+    interest: u32,
+}
+
+/// Returns whether every bit `interest` cares about is unchanged between `snapshot` and `live`.
+///
+/// Compares bit-by-bit rather than folding the interested bits into one combined number: summing
+/// or XORing per-bit versions together can let independent bumps cancel each other out, masking
+/// a real wakeup.
+fn versions_unchanged(snapshot: &Versions, live: &Versions, interest: u32) -> bool {
+    (0..INTEREST_BITS).all(|i| interest & (1 << i) == 0 || snapshot[i] == live[i])
 }
 
 impl NotifyOwned {
     pub fn new() -> Self {
         Self {
             shared: Arc::new(Mutex::new(Shared {
-                version: 0,
+                versions: [0; INTEREST_BITS],
                 wakers: Slab::new(),
+                order: VecDeque::new(),
+                permits: 0,
             })),
         }
     }
 
     pub fn notify_all(&self) {
+        self.shared.lock().unwrap().permits = 0;
+        self.notify_mask(u32::MAX);
+    }
+
+    /// Wake every waiter whose registered interest overlaps `mask`, and advance the version of
+    /// every bit `mask` sets, even if no waiter is currently interested in it.
+    ///
+    /// Waiters whose interest doesn't overlap `mask` are left registered.
+    pub fn notify_mask(&self, mask: u32) {
         let mut shared = self.shared.lock().unwrap();
-        shared.version = shared.version.wrapping_add(1);
+        for (i, version) in shared.versions.iter_mut().enumerate() {
+            if mask & (1 << i) != 0 {
+                *version = version.wrapping_add(1);
+            }
+        }
         let n = shared.wakers.len();
-        for waker in shared.wakers.drain() {
+        let matched: Vec<usize> = shared
+            .wakers
+            .iter()
+            .filter(|(_, entry)| entry.interest & mask != 0)
+            .map(|(key, _)| key)
+            .collect();
+        let wakers: Vec<Waker> = matched
+            .into_iter()
+            .map(|key| shared.wakers.remove(key).waker)
+            .collect();
+        // Limit unused slots, which cost iteration time as well as memory. Only safe once every
+        // waiter has been woken and removed: shrinking while non-matching waiters remain would
+        // invalidate the slab indices they're holding onto.
+        if shared.wakers.is_empty() {
+            if n * 4 < shared.wakers.capacity() {
+                shared.wakers = Slab::new();
+            }
+            // Every key still in `order` refers to a waiter that's now gone; drop them rather
+            // than letting `notify_one` discover that one stale key at a time.
+            shared.order.clear();
+        }
+        // Wake only after releasing the lock: a waker that synchronously re-enters `register` or
+        // `Waiter::drop` on this same `NotifyOwned` would otherwise deadlock on `Shared`'s mutex.
+        drop(shared);
+        for waker in wakers {
             waker.wake();
         }
-        // Limit unused slots, which cost iteration time as well as memory
-        if n * 4 < shared.wakers.capacity() {
-            shared.wakers = Slab::with_capacity(n);
+    }
+
+    /// Wake exactly one waiter, preferring the one that has been registered the longest,
+    /// regardless of its interest.
+    ///
+    /// If no waiter is currently registered, a permit is stored so that the next `Waiter` to
+    /// register with interest `u32::MAX` observes readiness immediately, mirroring tokio's
+    /// single-permit `Notify`. Registrations with a narrower interest leave the permit in place.
+    pub fn notify_one(&self) {
+        let mut shared = self.shared.lock().unwrap();
+        let waker = loop {
+            match shared.order.pop_front() {
+                // `order` isn't kept in sync with removals done by `notify_mask`/`Waiter::drop`,
+                // so a popped key may no longer be registered; skip it rather than treating it as
+                // the oldest waiter.
+                Some(key) if !shared.wakers.contains(key) => continue,
+                Some(key) => break Some(shared.wakers.remove(key).waker),
+                None => {
+                    shared.permits = 1;
+                    break None;
+                }
+            }
+        };
+        // Wake only after releasing the lock; see `notify_mask` for why.
+        drop(shared);
+        if let Some(waker) = waker {
+            waker.wake();
         }
     }
 
-    pub fn wait(&self) -> Waiter {
+    /// Returns a bare, unregistered `Waiter`. Callers must call `Waiter::register` before
+    /// readiness could occur, e.g. inside a lock guarding the state of interest, or a wakeup can
+    /// be lost; prefer `wait`/`notified`/`notified_interest` unless that manual control is needed.
+    pub fn waiter(&self) -> Waiter {
         Waiter {
             shared: self.shared.clone(),
             state: None,
         }
     }
+
+    /// Returns a future that resolves the next time `notify_all`/`notify_mask` fires, suitable
+    /// for `notify.wait().await`. Equivalent to `notified()`; see its docs for why this is immune
+    /// to the register-before-readiness footgun that a bare `Waiter` has.
+    pub fn wait(&self) -> Notified {
+        self.notified()
+    }
+
+    /// Returns a future that resolves the next time any bit of `interest` is notified via
+    /// `notify_mask`/`notify_all`, without the caller having to register a `Waiter` up front.
+    ///
+    /// Unlike `Waiter::register`, the readiness check here is not racy: the returned future
+    /// snapshots the current per-bit versions and, on every poll, compares them against the live
+    /// versions before touching the `Slab`. A `notify_mask` that races ahead of the first `poll`
+    /// (or of `.await`) is still observed, because it always advances the targeted bits' versions.
+    pub fn notified_interest(&self, interest: u32) -> Notified {
+        let versions = self.shared.lock().unwrap().versions;
+        Notified {
+            waiter: Waiter {
+                shared: self.shared.clone(),
+                state: None,
+            },
+            versions,
+            interest,
+        }
+    }
+
+    /// Equivalent to `notified_interest(u32::MAX)`: resolves on any `notify_mask`/`notify_all`.
+    pub fn notified(&self) -> Notified {
+        self.notified_interest(u32::MAX)
+    }
+
+    /// Returns the number of `Waiter`s currently registered, i.e. waiting to be woken
+    pub fn waiter_count(&self) -> usize {
+        self.shared.lock().unwrap().wakers.len()
+    }
+
+    /// Returns whether `waiter` has already been notified, i.e. whether its next poll would
+    /// observe readiness, without needing a `Context` to check.
+    ///
+    /// Useful for detecting a stuck task: a waiter registered against a version that has already
+    /// advanced is notified but simply hasn't been polled again yet.
+    pub fn is_notified(&self, waiter: &Waiter) -> bool {
+        let shared = self.shared.lock().unwrap();
+        match &waiter.state {
+            Some(State {
+                versions,
+                index,
+                interest,
+            }) => {
+                !shared.wakers.contains(*index)
+                    || !versions_unchanged(versions, &shared.versions, *interest)
+            }
+            None => shared.permits > 0,
+        }
+    }
+}
+
+impl fmt::Debug for NotifyOwned {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let shared = self.shared.lock().unwrap();
+        // Surface one combined version rather than the full per-bit array, to keep this
+        // readable; unlike `versions_unchanged`'s comparisons, a diagnostic summary doesn't need
+        // to be collision-free.
+        let version = shared
+            .versions
+            .iter()
+            .fold(0u64, |acc, v| acc.wrapping_add(*v));
+        f.debug_struct("NotifyOwned")
+            .field("version", &version)
+            .field("waiters", &shared.wakers.len())
+            .field("permits", &shared.permits)
+            .finish()
+    }
 }
 
 pub struct Waiter {
@@ -51,32 +229,90 @@ pub struct Waiter {
 }
 
 impl Waiter {
-    /// Register to be woken by the next `notify_all`. Must be called before readiness could occur,
+    /// Register interest in `interest`'s bits, to be woken by the next `notify_mask`/`notify_all`
+    /// that targets one of them, or by `notify_one`. Must be called before readiness could occur,
     /// e.g. inside a lock guarding the state of interest.
-    pub fn register(&mut self, ctx: &mut Context<'_>) {
+    ///
+    /// `interest` must stay the same across repeated calls for a given `Waiter`.
+    ///
+    /// Returns `true` if a stored `notify_one` permit was consumed, meaning this waiter is
+    /// already ready and does not need to wait for a future wakeup.
+    pub fn register(&mut self, ctx: &mut Context<'_>, interest: u32) -> bool {
         let mut shared = self.shared.lock().unwrap();
-        match self.state {
+        Self::register_locked(&mut self.state, &mut shared, ctx, interest)
+    }
+
+    /// Same as `register`, but reuses a `Shared` lock the caller is already holding, so a
+    /// readiness check against `shared` and the registration itself happen as one critical
+    /// section instead of two separate lock acquisitions with a gap a notification could land in.
+    ///
+    /// Takes `state` rather than `&mut Waiter` so callers that already hold `waiter.shared`
+    /// locked (as `shared`) can pass `&mut waiter.state` without re-borrowing `waiter` as a whole.
+    fn register_locked(
+        state: &mut Option<State>,
+        shared: &mut Shared,
+        ctx: &mut Context<'_>,
+        interest: u32,
+    ) -> bool {
+        // `notify_one` wakes the oldest waiter regardless of interest, so a stored permit is only
+        // meaningful to a registration that likewise doesn't care about interest; letting it
+        // satisfy an interest-scoped `notified_interest(mask)` would resolve readiness for bits
+        // that were never actually notified.
+        if shared.permits > 0 && interest == u32::MAX {
+            shared.permits = 0;
+            return true;
+        }
+        // A previously registered slot may already be gone, e.g. `notify_one` removed and woke it
+        // without bumping any version. Treat that the same as never having registered, rather
+        // than indexing (or silently aliasing, if the slot was reused) a dead slot.
+        if let Some(State { index, .. }) = state {
+            if !shared.wakers.contains(*index) {
+                *state = None;
+            }
+        }
+        match &*state {
             None => {
-                self.state = Some(State {
-                    version: shared.version,
-                    index: shared.wakers.insert(ctx.waker().clone()),
+                let index = shared.wakers.insert(Entry {
+                    waker: ctx.waker().clone(),
+                    interest,
+                });
+                shared.order.push_back(index);
+                *state = Some(State {
+                    versions: shared.versions,
+                    index,
+                    interest,
                 });
             }
-            Some(State { version, index }) => {
-                debug_assert!(shared.version == version);
-                shared.wakers[index] = ctx.waker().clone();
+            Some(State {
+                versions,
+                index,
+                interest: prev_interest,
+            }) => {
+                debug_assert!(*prev_interest == interest);
+                debug_assert!(versions_unchanged(versions, &shared.versions, interest));
+                shared.wakers[*index].waker = ctx.waker().clone();
             }
         }
+        false
     }
 }
 
 impl Drop for Waiter {
     fn drop(&mut self) {
-        if let Some(State { index, version }) = self.state {
+        if let Some(State {
+            index,
+            versions,
+            interest,
+        }) = &self.state
+        {
             if let Ok(mut shared) = self.shared.lock() {
-                // Ensure `wakers` doesn't grow if `Waiter`s are repeatedly constructed and dropped
-                if shared.version == version {
-                    shared.wakers.remove(index);
+                // Only remove if our slot hasn't already been taken out from under us, by
+                // `notify_mask` (which bumps the interested bits' versions and removes matching
+                // waiters) or `notify_one` (which removes without bumping anything).
+                if shared.wakers.contains(*index)
+                    && versions_unchanged(versions, &shared.versions, *interest)
+                {
+                    shared.wakers.remove(*index);
                 }
             }
         }
@@ -84,6 +320,174 @@ impl Drop for Waiter {
 }
 
 struct State {
-    version: u64,
+    /// Per-bit versions observed at the time of (re-)registration
+    versions: Versions,
     index: usize,
+    interest: u32,
+}
+
+/// A future that resolves the next time a [`NotifyOwned`] is notified with an overlapping
+/// interest mask, via [`NotifyOwned::notified`]/[`NotifyOwned::notified_interest`].
+pub struct Notified {
+    waiter: Waiter,
+    /// Per-bit versions observed when this future was created
+    versions: Versions,
+    interest: u32,
+}
+
+impl Future for Notified {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<()> {
+        let this = self.get_mut();
+        // The version check and the registration must happen under the same lock acquisition:
+        // otherwise a `notify_mask`/`notify_all` landing in the gap between two separate
+        // acquisitions would bump `versions` with nothing registered yet to wake, and the
+        // registration that follows would then read the already-bumped version as its baseline,
+        // permanently losing that wakeup instead of being the race-immune future this type
+        // promises.
+        let mut shared = this.waiter.shared.lock().unwrap();
+        if !versions_unchanged(&this.versions, &shared.versions, this.interest) {
+            return Poll::Ready(());
+        }
+        if Waiter::register_locked(&mut this.waiter.state, &mut shared, ctx, this.interest) {
+            return Poll::Ready(());
+        }
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use super::*;
+
+    /// A `Waker` that records whether it was woken, without needing an executor to poll futures.
+    struct TrackingWaker(AtomicBool);
+
+    impl std::task::Wake for TrackingWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    fn tracking_waker() -> (Waker, Arc<TrackingWaker>) {
+        let inner = Arc::new(TrackingWaker(AtomicBool::new(false)));
+        (Waker::from(inner.clone()), inner)
+    }
+
+    #[test]
+    fn notify_one_wakes_fifo() {
+        let notify = NotifyOwned::new();
+        let (waker_a, woken_a) = tracking_waker();
+        let (waker_b, woken_b) = tracking_waker();
+        let mut a = notify.waiter();
+        let mut b = notify.waiter();
+        a.register(&mut Context::from_waker(&waker_a), u32::MAX);
+        b.register(&mut Context::from_waker(&waker_b), u32::MAX);
+
+        notify.notify_one();
+        assert!(woken_a.0.load(Ordering::SeqCst), "oldest waiter wakes first");
+        assert!(!woken_b.0.load(Ordering::SeqCst));
+
+        notify.notify_one();
+        assert!(woken_b.0.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn notify_one_stores_permit_when_no_waiter_registered() {
+        let notify = NotifyOwned::new();
+        notify.notify_one();
+
+        let mut waiter = notify.waiter();
+        let (waker, _woken) = tracking_waker();
+        assert!(waiter.register(&mut Context::from_waker(&waker), u32::MAX));
+    }
+
+    #[test]
+    fn notify_mask_only_wakes_overlapping_interest() {
+        let notify = NotifyOwned::new();
+        let (waker_read, woken_read) = tracking_waker();
+        let (waker_write, woken_write) = tracking_waker();
+        let mut readable = notify.waiter();
+        let mut writable = notify.waiter();
+        readable.register(&mut Context::from_waker(&waker_read), 0b01);
+        writable.register(&mut Context::from_waker(&waker_write), 0b10);
+
+        notify.notify_mask(0b01);
+        assert!(woken_read.0.load(Ordering::SeqCst));
+        assert!(!woken_write.0.load(Ordering::SeqCst), "unrelated interest stays registered");
+        assert_eq!(notify.waiter_count(), 1);
+    }
+
+    #[test]
+    fn per_bit_versions_dont_cancel_across_bits() {
+        // Regression test: folding per-bit versions into one combined number can let an
+        // independent bump on bit 0 mask a real bump on bit 1 (or vice versa), losing a wakeup.
+        let notify = NotifyOwned::new();
+        let notified = notify.notified_interest(0b10);
+        notify.notify_mask(0b01);
+        notify.notify_mask(0b10);
+
+        let (waker, _woken) = tracking_waker();
+        let mut notified = std::pin::pin!(notified);
+        assert_eq!(
+            notified.as_mut().poll(&mut Context::from_waker(&waker)),
+            Poll::Ready(())
+        );
+    }
+
+    #[test]
+    fn notified_observes_a_notify_that_races_ahead_of_the_first_poll() {
+        let notify = NotifyOwned::new();
+        let notified = notify.notified();
+        // Unlike a bare `Waiter`, `Notified` snapshots the version at creation time, so a
+        // `notify_all` before the first `poll` (the register-before-readiness footgun) must
+        // still be observed rather than lost.
+        notify.notify_all();
+
+        let (waker, _woken) = tracking_waker();
+        let mut notified = std::pin::pin!(notified);
+        assert_eq!(
+            notified.as_mut().poll(&mut Context::from_waker(&waker)),
+            Poll::Ready(())
+        );
+    }
+
+    #[test]
+    fn waiter_count_is_notified_and_debug() {
+        let notify = NotifyOwned::new();
+        assert_eq!(notify.waiter_count(), 0);
+
+        let mut waiter = notify.waiter();
+        let (waker, _woken) = tracking_waker();
+        waiter.register(&mut Context::from_waker(&waker), u32::MAX);
+        assert_eq!(notify.waiter_count(), 1);
+        assert!(!notify.is_notified(&waiter));
+
+        notify.notify_all();
+        assert!(notify.is_notified(&waiter));
+
+        let debug = format!("{notify:?}");
+        assert!(debug.contains("NotifyOwned"));
+        assert!(debug.contains("version"));
+        assert!(!debug.contains('['), "Debug should summarize, not dump the per-bit array");
+    }
+
+    #[test]
+    fn stray_notify_one_permit_does_not_satisfy_unrelated_interest() {
+        // Regression test: a permit stored by `notify_one` (because nothing was registered yet)
+        // must not be mistaken for a `notify_mask`/`notify_all` on bits it has nothing to do with.
+        let notify = NotifyOwned::new();
+        notify.notify_one();
+
+        let notified = notify.notified_interest(0x1);
+        let (waker, _woken) = tracking_waker();
+        let mut notified = std::pin::pin!(notified);
+        assert_eq!(
+            notified.as_mut().poll(&mut Context::from_waker(&waker)),
+            Poll::Pending
+        );
+    }
 }
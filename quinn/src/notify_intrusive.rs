@@ -0,0 +1,269 @@
+//! A `no_std`, allocation-free counterpart to [`notify`](crate::notify).
+//!
+//! [`notify::NotifyOwned`](crate::notify::NotifyOwned) needs `std::sync::Mutex` plus a
+//! `Slab<Waker>` allocation per registered waiter. Neither is available in embedded QUIC builds
+//! without `alloc`. `Notify` here instead threads an intrusive doubly linked list through the
+//! `Waiter` nodes themselves: each `Waiter` is pinned in place and owns the storage for its own
+//! list node, so no allocation happens on `register`, and `Notify` shrinks to a list head/tail
+//! pair plus a small spinlock.
+//!
+//! # Safety invariant
+//!
+//! A node is unlinked from the list *exactly once*: either by `Drop` (if it is still linked), or
+//! by `notify_all` (which unlinks as it wakes). Both always take `Notify`'s lock first, so a
+//! `notify_all` that has already unlinked and woken a node can never race with that node's
+//! `Drop` deciding whether to unlink it.
+
+use core::cell::{Cell, UnsafeCell};
+use core::marker::PhantomPinned;
+use core::pin::Pin;
+use core::ptr::NonNull;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context, Waker};
+
+/// Broadcasts an event to any number of pinned [`Waiter`]s without allocating
+pub struct Notify {
+    lock: SpinLock,
+    list: UnsafeCell<List>,
+}
+
+// SAFETY: all access to `list` is guarded by `lock`.
+unsafe impl Sync for Notify {}
+unsafe impl Send for Notify {}
+
+struct List {
+    head: Option<NonNull<Node>>,
+    tail: Option<NonNull<Node>>,
+}
+
+impl Notify {
+    pub const fn new() -> Self {
+        Self {
+            lock: SpinLock::new(),
+            list: UnsafeCell::new(List {
+                head: None,
+                tail: None,
+            }),
+        }
+    }
+
+    /// Wake every currently registered `Waiter` and mark it notified
+    pub fn notify_all(&self) {
+        loop {
+            let guard = self.lock.lock();
+            // SAFETY: `list` is only ever accessed while `lock` is held.
+            let list = unsafe { &mut *self.list.get() };
+            let Some(mut node) = list.head else {
+                return;
+            };
+            // SAFETY: `node` is linked, so it points at a live `Node` owned by a `Waiter` that
+            // has not yet been dropped (Drop unlinks before deallocating).
+            let node_ref = unsafe { node.as_mut() };
+            Self::unlink(list, node);
+            node_ref.linked.set(false);
+            node_ref.notified.store(true, Ordering::Release);
+            // SAFETY: only the thread holding `lock` touches `waker`, and we're about to drop
+            // the guard before calling `wake()` so a re-entrant `register`/`drop` can proceed.
+            let waker = unsafe { (*node_ref.waker.get()).take() };
+            drop(guard);
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+        }
+    }
+
+    fn unlink(list: &mut List, mut node: NonNull<Node>) {
+        // SAFETY: caller holds `lock`, and `node` is currently linked into `list`.
+        let node = unsafe { node.as_mut() };
+        match node.prev.get() {
+            Some(mut prev) => unsafe { prev.as_mut() }.next.set(node.next.get()),
+            None => list.head = node.next.get(),
+        }
+        match node.next.get() {
+            Some(mut next) => unsafe { next.as_mut() }.prev.set(node.prev.get()),
+            None => list.tail = node.prev.get(),
+        }
+        node.prev.set(None);
+        node.next.set(None);
+    }
+}
+
+impl Default for Notify {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct Node {
+    waker: UnsafeCell<Option<Waker>>,
+    notified: AtomicBool,
+    linked: Cell<bool>,
+    prev: Cell<Option<NonNull<Node>>>,
+    next: Cell<Option<NonNull<Node>>>,
+}
+
+/// A registration point for one task to wait on a [`Notify`]
+///
+/// Must be pinned before `register` is called; the pin is what makes it sound for `Notify` to
+/// hold raw pointers into `self.node` while this value is alive.
+pub struct Waiter<'a> {
+    notify: &'a Notify,
+    node: Node,
+    _pin: PhantomPinned,
+}
+
+impl<'a> Waiter<'a> {
+    pub fn new(notify: &'a Notify) -> Self {
+        Self {
+            notify,
+            node: Node {
+                waker: UnsafeCell::new(None),
+                notified: AtomicBool::new(false),
+                linked: Cell::new(false),
+                prev: Cell::new(None),
+                next: Cell::new(None),
+            },
+            _pin: PhantomPinned,
+        }
+    }
+
+    /// Returns `true` if a `notify_all` has woken this waiter since it was created or last linked
+    /// into the list by `register`
+    pub fn is_notified(&self) -> bool {
+        self.node.notified.load(Ordering::Acquire)
+    }
+
+    /// Register to be woken by the next `notify_all`. Must be called before readiness could
+    /// occur, e.g. inside a lock guarding the state of interest.
+    pub fn register(self: Pin<&mut Self>, ctx: &mut Context<'_>) {
+        // SAFETY: we only ever move `node` into the list via its address, never relocate it.
+        let this = unsafe { self.get_unchecked_mut() };
+        let guard = this.notify.lock.lock();
+        // SAFETY: `waker` is only ever touched while holding `notify.lock`.
+        unsafe { *this.node.waker.get() = Some(ctx.waker().clone()) };
+        if !this.node.linked.get() {
+            // SAFETY: `list` is only ever accessed while `lock` is held.
+            let list = unsafe { &mut *this.notify.list.get() };
+            let node: NonNull<Node> = NonNull::from(&this.node);
+            this.node.prev.set(list.tail);
+            this.node.next.set(None);
+            match list.tail {
+                // SAFETY: `tail`, if set, points at a linked, live node.
+                Some(mut tail) => unsafe { tail.as_mut() }.next.set(Some(node)),
+                None => list.head = Some(node),
+            }
+            list.tail = Some(node);
+            this.node.linked.set(true);
+            this.node.notified.store(false, Ordering::Release);
+        }
+        drop(guard);
+    }
+}
+
+impl Drop for Waiter<'_> {
+    fn drop(&mut self) {
+        let guard = self.notify.lock.lock();
+        if self.node.linked.get() {
+            // SAFETY: `list` is only ever accessed while `lock` is held, and `self.node` is
+            // currently linked into it.
+            let list = unsafe { &mut *self.notify.list.get() };
+            Notify::unlink(list, NonNull::from(&self.node));
+            self.node.linked.set(false);
+        }
+        drop(guard);
+    }
+}
+
+struct SpinLock(AtomicBool);
+
+impl SpinLock {
+    const fn new() -> Self {
+        Self(AtomicBool::new(false))
+    }
+
+    fn lock(&self) -> SpinLockGuard<'_> {
+        while self
+            .0
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            core::hint::spin_loop();
+        }
+        SpinLockGuard(self)
+    }
+}
+
+struct SpinLockGuard<'a>(&'a SpinLock);
+
+impl Drop for SpinLockGuard<'_> {
+    fn drop(&mut self) {
+        self.0 .0.store(false, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::pin::pin;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Arc;
+    use std::task::Context;
+
+    use super::*;
+
+    struct TrackingWaker(AtomicBool);
+
+    impl std::task::Wake for TrackingWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    fn tracking_waker() -> (std::task::Waker, Arc<TrackingWaker>) {
+        let inner = Arc::new(TrackingWaker(AtomicBool::new(false)));
+        (std::task::Waker::from(inner.clone()), inner)
+    }
+
+    #[test]
+    fn notify_all_wakes_and_marks_registered_waiters() {
+        let notify = Notify::new();
+        let mut waiter = pin!(Waiter::new(&notify));
+        let (waker, woken) = tracking_waker();
+        waiter.as_mut().register(&mut Context::from_waker(&waker));
+        assert!(!waiter.is_notified());
+
+        notify.notify_all();
+        assert!(woken.0.load(Ordering::SeqCst));
+        assert!(waiter.is_notified());
+    }
+
+    #[test]
+    fn dropping_an_unlinked_waiter_is_a_no_op() {
+        // A `Waiter` that never registered, or whose node `notify_all` already unlinked and
+        // woke, must not be unlinked a second time by `Drop`.
+        let notify = Notify::new();
+        {
+            let _never_registered = pin!(Waiter::new(&notify));
+        }
+
+        let mut waiter = pin!(Waiter::new(&notify));
+        let (waker, _woken) = tracking_waker();
+        waiter.as_mut().register(&mut Context::from_waker(&waker));
+        notify.notify_all();
+        assert!(waiter.is_notified());
+        // `waiter`'s node is already unlinked at this point; letting it go out of scope here must
+        // not panic or corrupt the (now empty) list.
+    }
+
+    #[test]
+    fn dropping_a_still_registered_waiter_unlinks_it() {
+        let notify = Notify::new();
+        {
+            let mut waiter = pin!(Waiter::new(&notify));
+            let (waker, _woken) = tracking_waker();
+            waiter.as_mut().register(&mut Context::from_waker(&waker));
+        }
+        // The dropped waiter above must have unlinked itself; a `notify_all` with no remaining
+        // registrations should simply find an empty list rather than touching freed memory.
+        notify.notify_all();
+    }
+}
@@ -0,0 +1,305 @@
+use std::future::Future;
+use std::ops::Deref;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::{Context, Poll, Waker};
+
+use slab::Slab;
+
+/// Creates a `watch` channel, returning the sender and an initial receiver
+///
+/// Reuses the version-counter-plus-`Slab<Waker>` broadcast core from `notify`: `send`/
+/// `send_modify` replace the value, bump the version and wake every registered waker exactly
+/// like `NotifyOwned::notify_all`, while receivers compare their last-seen version against the
+/// live one to decide whether `changed()` should resolve immediately.
+pub fn channel<T>(value: T) -> (Sender<T>, Receiver<T>) {
+    let shared = Arc::new(Mutex::new(Shared {
+        value,
+        version: 0,
+        wakers: Slab::new(),
+        sender_dropped: false,
+    }));
+    let sender = Sender {
+        shared: shared.clone(),
+    };
+    let receiver = Receiver {
+        shared,
+        version: 0,
+        state: None,
+    };
+    (sender, receiver)
+}
+
+struct Shared<T> {
+    value: T,
+    version: u64,
+    wakers: Slab<Waker>,
+    sender_dropped: bool,
+}
+
+impl<T> Shared<T> {
+    fn wake_all(&mut self) -> Vec<Waker> {
+        self.version = self.version.wrapping_add(1);
+        self.drain_wakers()
+    }
+
+    /// Take every registered waker out without bumping `version`, so that a `Changed` in flight
+    /// doesn't mistake this wakeup for a new value having been sent.
+    ///
+    /// Returns the wakers rather than calling `.wake()` itself: the caller holds `Shared`'s lock,
+    /// and a woken task that synchronously re-enters `Changed::poll` or `Receiver::drop` on this
+    /// same channel would otherwise deadlock on the mutex.
+    fn drain_wakers(&mut self) -> Vec<Waker> {
+        let n = self.wakers.len();
+        let wakers: Vec<Waker> = self.wakers.drain().collect();
+        // Limit unused slots, which cost iteration time as well as memory
+        if n * 4 < self.wakers.capacity() {
+            self.wakers = Slab::with_capacity(n);
+        }
+        wakers
+    }
+}
+
+/// The sending half of a `watch` channel, created by `channel`
+pub struct Sender<T> {
+    shared: Arc<Mutex<Shared<T>>>,
+}
+
+impl<T> Sender<T> {
+    /// Replace the current value and wake every receiver waiting on `changed`
+    pub fn send(&self, value: T) {
+        let mut shared = self.shared.lock().unwrap();
+        shared.value = value;
+        let wakers = shared.wake_all();
+        drop(shared);
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+
+    /// Update the current value in place and wake every receiver waiting on `changed`
+    pub fn send_modify(&self, modify: impl FnOnce(&mut T)) {
+        let mut shared = self.shared.lock().unwrap();
+        modify(&mut shared.value);
+        let wakers = shared.wake_all();
+        drop(shared);
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+
+    /// Create a new receiver, initialized to the current value
+    pub fn subscribe(&self) -> Receiver<T> {
+        let shared = self.shared.lock().unwrap();
+        Receiver {
+            shared: self.shared.clone(),
+            version: shared.version,
+            state: None,
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut shared = self.shared.lock().unwrap();
+        shared.sender_dropped = true;
+        // Don't bump `version`: doing so would make a pending `Changed` see a version mismatch
+        // and return `Ok(())` for a value that never actually changed, instead of observing
+        // `sender_dropped` and returning `Err`.
+        let wakers = shared.drain_wakers();
+        drop(shared);
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+}
+
+/// The receiving half of a `watch` channel, created by `channel` or `Sender::subscribe`
+pub struct Receiver<T> {
+    shared: Arc<Mutex<Shared<T>>>,
+    /// Last version observed by this receiver, via `changed` or `borrow_and_update`
+    version: u64,
+    state: Option<State>,
+}
+
+struct State {
+    version: u64,
+    index: usize,
+}
+
+/// Error returned by `Receiver::changed` when the `Sender` has been dropped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SenderDropped;
+
+impl<T> Receiver<T> {
+    /// Returns a future that resolves once the value has changed since the last call to
+    /// `changed` or `borrow_and_update`, or errors if the `Sender` was dropped without sending a
+    /// further update.
+    pub fn changed(&mut self) -> Changed<'_, T> {
+        Changed { receiver: self }
+    }
+
+    /// Marks the current value as seen and returns a guard granting read access to it
+    pub fn borrow_and_update(&mut self) -> Ref<'_, T> {
+        let shared = self.shared.lock().unwrap();
+        self.version = shared.version;
+        Ref { shared }
+    }
+
+    /// Returns a guard granting read access to the current value, without marking it as seen
+    pub fn borrow(&self) -> Ref<'_, T> {
+        Ref {
+            shared: self.shared.lock().unwrap(),
+        }
+    }
+}
+
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        Self {
+            shared: self.shared.clone(),
+            version: self.version,
+            state: None,
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        if let Some(State { index, version }) = self.state {
+            if let Ok(mut shared) = self.shared.lock() {
+                // Only remove if our slot hasn't already been taken out from under us, by
+                // `wake_all` (which bumps `version` and drains every waker) or `drain_wakers` on
+                // sender drop (which drains without bumping `version`).
+                if shared.version == version && shared.wakers.contains(index) {
+                    shared.wakers.remove(index);
+                }
+            }
+        }
+    }
+}
+
+/// Guard granting read access to a `watch` channel's current value, via `Receiver::borrow` or
+/// `Receiver::borrow_and_update`
+pub struct Ref<'a, T> {
+    shared: MutexGuard<'a, Shared<T>>,
+}
+
+impl<T> Deref for Ref<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.shared.value
+    }
+}
+
+/// Future returned by `Receiver::changed`
+pub struct Changed<'a, T> {
+    receiver: &'a mut Receiver<T>,
+}
+
+impl<T> Future for Changed<'_, T> {
+    type Output = Result<(), SenderDropped>;
+
+    fn poll(self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let mut shared = this.receiver.shared.lock().unwrap();
+        if shared.version != this.receiver.version {
+            // `version` only ever advances alongside `wake_all`, which already drained (and thus
+            // removed) every registered waker, including ours if we had one. Don't try to remove
+            // it again: the slot is either gone (panics on `remove`) or has since been reused by
+            // an unrelated registration (would remove the wrong waker).
+            this.receiver.state = None;
+            this.receiver.version = shared.version;
+            return Poll::Ready(Ok(()));
+        }
+        if shared.sender_dropped {
+            // `Sender::drop` already drained (and thus removed) every registered waker, including
+            // ours if we had one, without bumping `version`. Clear our stale `state` so `Receiver`
+            // doesn't later try to remove an already-gone (or since-reused) slab slot.
+            this.receiver.state = None;
+            return Poll::Ready(Err(SenderDropped));
+        }
+        match this.receiver.state {
+            None => {
+                this.receiver.state = Some(State {
+                    version: shared.version,
+                    index: shared.wakers.insert(ctx.waker().clone()),
+                });
+            }
+            Some(State { version, index }) => {
+                debug_assert!(shared.version == version);
+                shared.wakers[index] = ctx.waker().clone();
+            }
+        }
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use super::*;
+
+    struct TrackingWaker(AtomicBool);
+
+    impl std::task::Wake for TrackingWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    fn tracking_waker() -> (Waker, Arc<TrackingWaker>) {
+        let inner = Arc::new(TrackingWaker(AtomicBool::new(false)));
+        (Waker::from(inner.clone()), inner)
+    }
+
+    fn poll_changed<T>(
+        changed: &mut Changed<'_, T>,
+        waker: &Waker,
+    ) -> Poll<Result<(), SenderDropped>> {
+        Pin::new(changed).poll(&mut Context::from_waker(waker))
+    }
+
+    #[test]
+    fn send_wakes_a_registered_receiver() {
+        let (tx, mut rx) = channel(0);
+        let (waker, woken) = tracking_waker();
+        assert_eq!(poll_changed(&mut rx.changed(), &waker), Poll::Pending);
+
+        tx.send(1);
+        assert!(woken.0.load(Ordering::SeqCst));
+        assert_eq!(poll_changed(&mut rx.changed(), &waker), Poll::Ready(Ok(())));
+        assert_eq!(*rx.borrow(), 1);
+    }
+
+    #[test]
+    fn dropping_sender_reports_sender_dropped_without_panicking_on_receiver_drop() {
+        // Regression test: a receiver that registered, observed `SenderDropped`, and was then
+        // dropped used to panic trying to remove its already-drained slab slot.
+        let (tx, mut rx) = channel(0);
+        let (waker, _woken) = tracking_waker();
+        assert_eq!(poll_changed(&mut rx.changed(), &waker), Poll::Pending);
+
+        drop(tx);
+        assert_eq!(
+            poll_changed(&mut rx.changed(), &waker),
+            Poll::Ready(Err(SenderDropped))
+        );
+        drop(rx);
+    }
+
+    #[test]
+    fn sender_drop_does_not_mask_as_a_changed_value() {
+        // Regression test: `Sender::drop` must not bump `version`, or a pending `Changed` would
+        // observe a version mismatch and return `Ok(())` for a value that never changed.
+        let (tx, mut rx) = channel(0);
+        drop(tx);
+        let (waker, _woken) = tracking_waker();
+        assert_eq!(
+            poll_changed(&mut rx.changed(), &waker),
+            Poll::Ready(Err(SenderDropped))
+        );
+    }
+}